@@ -12,6 +12,7 @@
 //!
 extern crate alga;
 extern crate assimp;
+extern crate assimp_sys;
 extern crate glfw;
 extern crate kiss3d;
 extern crate nalgebra as na;
@@ -23,6 +24,17 @@ extern crate log;
 extern crate structopt;
 #[macro_use]
 extern crate structopt_derive;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate image;
+
+mod watch;
+mod server;
+mod mesh_cache;
+mod trajectory;
+
+pub use trajectory::{Player, Trajectory};
 
 use assimp::{Importer, LogStream};
 use kiss3d::resource::Mesh;
@@ -40,15 +52,107 @@ fn get_cache_dir() -> &'static str {
     "/tmp/urdf_viz/"
 }
 
-pub fn load_mesh<P>(filename: P) -> Result<Vec<Rc<RefCell<Mesh>>>, String>
+fn get_mesh_cache_dir() -> PathBuf {
+    Path::new(get_cache_dir()).join("mesh_cache")
+}
+
+pub struct LoadedMesh {
+    pub mesh: Rc<RefCell<Mesh>>,
+    pub diffuse_texture: Option<PathBuf>,
+    vertices: Vec<na::Point3<f32>>,
+    indices: Vec<na::Point3<u32>>,
+    normals: Option<Vec<na::Vector3<f32>>>,
+    uvs: Option<Vec<na::Point2<f32>>>,
+}
+
+impl LoadedMesh {
+    fn new(vertices: Vec<na::Point3<f32>>,
+           indices: Vec<na::Point3<u32>>,
+           normals: Option<Vec<na::Vector3<f32>>>,
+           uvs: Option<Vec<na::Point2<f32>>>,
+           diffuse_texture: Option<PathBuf>)
+           -> LoadedMesh {
+        let mesh = Mesh::new(vertices.clone(),
+                              indices.clone(),
+                              normals.clone(),
+                              uvs.clone(),
+                              false);
+        LoadedMesh {
+            mesh: Rc::new(RefCell::new(mesh)),
+            diffuse_texture: diffuse_texture,
+            vertices: vertices,
+            indices: indices,
+            normals: normals,
+            uvs: uvs,
+        }
+    }
+
+    fn to_cached(&self) -> mesh_cache::CachedMesh {
+        mesh_cache::CachedMesh {
+            vertices: self.vertices.iter().map(|v| [v.x, v.y, v.z]).collect(),
+            indices: self.indices.iter().map(|i| [i.x, i.y, i.z]).collect(),
+            normals: self.normals
+                .as_ref()
+                .map(|ns| ns.iter().map(|v| [v.x, v.y, v.z]).collect()),
+            uvs: self.uvs.as_ref().map(|us| us.iter().map(|v| [v.x, v.y]).collect()),
+            diffuse_texture: self.diffuse_texture
+                .as_ref()
+                .and_then(|p| p.to_str())
+                .map(|s| s.to_owned()),
+        }
+    }
+
+    fn from_cached(cached: mesh_cache::CachedMesh) -> LoadedMesh {
+        let vertices = cached
+            .vertices
+            .iter()
+            .map(|v| na::Point3::new(v[0], v[1], v[2]))
+            .collect();
+        let indices = cached
+            .indices
+            .iter()
+            .map(|i| na::Point3::new(i[0], i[1], i[2]))
+            .collect();
+        let normals = cached
+            .normals
+            .map(|ns| ns.iter().map(|v| na::Vector3::new(v[0], v[1], v[2])).collect());
+        let uvs = cached
+            .uvs
+            .map(|us| us.iter().map(|v| na::Point2::new(v[0], v[1])).collect());
+        let diffuse_texture = cached.diffuse_texture.map(PathBuf::from);
+        LoadedMesh::new(vertices, indices, normals, uvs, diffuse_texture)
+    }
+}
+
+pub fn load_mesh<P>(filename: P, scale: [f64; 3]) -> Result<Vec<LoadedMesh>, String>
     where P: AsRef<Path>
 {
+    let cache_dir = get_mesh_cache_dir();
+    let key = mesh_cache::cache_key(filename.as_ref(), scale).ok();
+    if let Some(key) = key {
+        if let Some(cached) = mesh_cache::load(&cache_dir, key) {
+            return Ok(cached.into_iter().map(LoadedMesh::from_cached).collect());
+        }
+    }
+
     let mut importer = Importer::new();
     importer.pre_transform_vertices(|x| x.enable = true);
     importer.collada_ignore_up_direction(true);
     if let Some(file) = filename.as_ref().to_str() {
         if let Ok(as_scene) = importer.read_file(file) {
-            Ok(convert_assimp_scene_to_kiss3d_meshes(as_scene))
+            let mesh_dir = filename
+                .as_ref()
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .to_owned();
+            let meshes = convert_assimp_scene_to_kiss3d_meshes(as_scene, &mesh_dir);
+            if let Some(key) = key {
+                let cacheable = meshes.iter().map(LoadedMesh::to_cached).collect::<Vec<_>>();
+                if let Err(e) = mesh_cache::store(&cache_dir, key, &cacheable) {
+                    error!("failed to write mesh cache for {}: {}", file, e);
+                }
+            }
+            Ok(meshes)
         } else {
             Err(format!("failed to read file in assimp {}", file))
         }
@@ -57,21 +161,101 @@ pub fn load_mesh<P>(filename: P) -> Result<Vec<Rc<RefCell<Mesh>>>, String>
     }
 }
 
-fn convert_assimp_scene_to_kiss3d_meshes(scene: assimp::Scene) -> Vec<Rc<RefCell<Mesh>>> {
+fn compute_face_normals(vertices: &[na::Point3<f32>],
+                         indices: &[na::Point3<u32>])
+                         -> Vec<na::Vector3<f32>> {
+    let mut normals = vec![na::Vector3::new(0.0f32, 0.0, 0.0); vertices.len()];
+    for face in indices {
+        let a = vertices[face[0] as usize];
+        let b = vertices[face[1] as usize];
+        let c = vertices[face[2] as usize];
+        let face_normal = (b - a).cross(&(c - a));
+        normals[face[0] as usize] += face_normal;
+        normals[face[1] as usize] += face_normal;
+        normals[face[2] as usize] += face_normal;
+    }
+    for normal in &mut normals {
+        // A zero-length accumulated normal (isolated or degenerate
+        // triangle) has no well-defined direction; leave it as the zero
+        // vector rather than normalizing NaN into it.
+        if normal.norm_squared() > 0.0 {
+            *normal = na::Unit::new_normalize(*normal).into_inner();
+        }
+    }
+    normals
+}
+
+// assimp's high-level `Material` wrapper exposes no texture query, so
+// this reaches past it into the raw `aiGetMaterialTexture` FFI call.
+fn resolve_diffuse_texture(scene: &assimp::Scene,
+                            material_index: u32,
+                            mesh_dir: &Path)
+                            -> Option<PathBuf> {
+    let material = scene.material_iter().nth(material_index as usize)?;
+    let mut texture_path = assimp_sys::AiString::default();
+    let result = unsafe {
+        assimp_sys::aiGetMaterialTexture(material.to_raw(),
+                                          assimp_sys::AiTextureType::Diffuse,
+                                          0,
+                                          &mut texture_path,
+                                          std::ptr::null(),
+                                          std::ptr::null_mut(),
+                                          std::ptr::null_mut(),
+                                          std::ptr::null_mut(),
+                                          std::ptr::null_mut(),
+                                          std::ptr::null_mut())
+    };
+    if result != assimp_sys::AiReturn::Success {
+        return None;
+    }
+    let relative: &str = texture_path.as_ref();
+    if relative.is_empty() {
+        return None;
+    }
+    let resolved = mesh_dir.join(relative);
+    // Some exporters bake in an absolute path, an embedded-texture
+    // reference like "*0", or a path that's simply gone missing; only
+    // hand back a texture kiss3d can actually decode, since
+    // `TextureManager::add` panics on a missing or undecodable file.
+    if resolved.exists() && image::open(&resolved).is_ok() {
+        Some(resolved)
+    } else {
+        None
+    }
+}
+
+fn convert_assimp_scene_to_kiss3d_meshes(scene: assimp::Scene, mesh_dir: &Path) -> Vec<LoadedMesh> {
     scene
         .mesh_iter()
         .map(|mesh| {
             let vertices = mesh.vertex_iter()
                 .map(|v| na::Point3::new(v.x, v.y, v.z))
                 .collect::<Vec<_>>();
-            let indices = mesh.face_iter()
+            let indices: Vec<na::Point3<u32>> = mesh.face_iter()
                 .filter_map(|f| if f.num_indices == 3 {
                                 Some(na::Point3::new(f[0], f[1], f[2]))
                             } else {
                                 None
                             })
                 .collect();
-            Rc::new(RefCell::new(Mesh::new(vertices, indices, None, None, false)))
+            let normals = if mesh.num_vertices() > 0 && mesh.has_normals() {
+                Some(mesh.normal_iter()
+                         .map(|n| na::Vector3::new(n.x, n.y, n.z))
+                         .collect::<Vec<_>>())
+            } else if !indices.is_empty() {
+                Some(compute_face_normals(&vertices, &indices))
+            } else {
+                None
+            };
+            let uvs = if mesh.has_texture_coords(0) {
+                Some(mesh.texture_coords_iter(0)
+                         .map(|uv| na::Point2::new(uv.x, uv.y))
+                         .collect::<Vec<_>>())
+            } else {
+                None
+            };
+            let diffuse_texture = resolve_diffuse_texture(&scene, mesh.material_index, mesh_dir);
+            LoadedMesh::new(vertices, indices, normals, uvs, diffuse_texture)
         })
         .collect()
 }
@@ -143,14 +327,23 @@ fn add_geometry(visual: &urdf_rs::Visual,
                 base_dir: &Path,
                 window: &mut Window)
                 -> Option<SceneNode> {
-    let mut geom = match visual.geometry {
+    let rgba = &visual.material.color.rgba;
+    match visual.geometry {
         urdf_rs::Geometry::Box { ref size } => {
-            Some(window.add_cube(size[0] as f32, size[1] as f32, size[2] as f32))
+            let mut obj = window.add_cube(size[0] as f32, size[1] as f32, size[2] as f32);
+            obj.set_color(rgba[0] as f32, rgba[1] as f32, rgba[2] as f32);
+            Some(obj)
         }
         urdf_rs::Geometry::Cylinder { radius, length } => {
-            Some(window.add_cylinder(radius as f32, length as f32))
+            let mut obj = window.add_cylinder(radius as f32, length as f32);
+            obj.set_color(rgba[0] as f32, rgba[1] as f32, rgba[2] as f32);
+            Some(obj)
+        }
+        urdf_rs::Geometry::Sphere { radius } => {
+            let mut obj = window.add_sphere(radius as f32);
+            obj.set_color(rgba[0] as f32, rgba[1] as f32, rgba[2] as f32);
+            Some(obj)
         }
-        urdf_rs::Geometry::Sphere { radius } => Some(window.add_sphere(radius as f32)),
         urdf_rs::Geometry::Mesh {
             ref filename,
             scale,
@@ -163,23 +356,37 @@ fn add_geometry(visual: &urdf_rs::Visual,
             }
             let na_scale = na::Vector3::new(scale[0] as f32, scale[1] as f32, scale[2] as f32);
 
-            if let Ok(meshes) = load_mesh(path) {
+            if let Ok(meshes) = load_mesh(path, scale) {
                 let mut group = window.add_group();
-                for mesh in meshes {
-                    group.add_mesh(mesh.clone(), na_scale);
+                for loaded in meshes {
+                    let mut node = group.add_mesh(loaded.mesh.clone(), na_scale);
+                    match loaded.diffuse_texture {
+                        // A texture already carries its own color
+                        // information; only fall back to the URDF
+                        // material color for sub-meshes that have none.
+                        Some(texture_path) => {
+                            // kiss3d's TextureManager caches by this name
+                            // alone and returns the first-loaded texture
+                            // on a repeat name, so the full resolved path
+                            // is used rather than just the file stem:
+                            // different parts routinely reuse names like
+                            // "diffuse.png".
+                            let texture_name = texture_path.to_str().unwrap_or("diffuse");
+                            node.set_texture_from_file(&texture_path, texture_name);
+                        }
+                        None => node.set_color(rgba[0] as f32, rgba[1] as f32, rgba[2] as f32),
+                    }
                 }
                 Some(group)
             } else {
                 None
             }
         }
-    };
-    let rgba = &visual.material.color.rgba;
-    match geom {
-        Some(ref mut obj) => obj.set_color(rgba[0] as f32, rgba[1] as f32, rgba[2] as f32),
-        None => return None,
     }
-    geom
+}
+
+fn link_signature(link: &urdf_rs::Link) -> String {
+    format!("{:?}", link.visual)
 }
 
 pub struct Viewer {
@@ -190,6 +397,15 @@ pub struct Viewer {
     font_map: HashMap<i32, Rc<kiss3d::text::Font>>,
     font_data: &'static [u8],
     original_colors: HashMap<String, na::Point3<f32>>,
+    watcher: Option<watch::AssetWatcher>,
+    link_signatures: HashMap<String, String>,
+    remote: Option<server::RemoteServer>,
+    recording: Option<Recording>,
+}
+
+struct Recording {
+    dir: PathBuf,
+    frame: u64,
 }
 
 impl Viewer {
@@ -204,6 +420,88 @@ impl Viewer {
             font_map: HashMap::new(),
             font_data: include_bytes!("font/Inconsolata.otf"),
             original_colors: HashMap::new(),
+            watcher: None,
+            link_signatures: HashMap::new(),
+            remote: None,
+            recording: None,
+        }
+    }
+    pub fn save_screenshot<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        create_parent_dir(path).map_err(|e| e.to_string())?;
+        // `Window::snap_image` already flips GL's bottom-left origin to
+        // PNG's top-left one internally, so the buffer it returns can be
+        // saved as-is.
+        self.window
+            .snap_image()
+            .save(path)
+            .map_err(|e| format!("failed to save screenshot {:?}: {}", path, e))
+    }
+    // NOTE: capture resolution is currently tied to the on-screen window
+    // size. kiss3d does not expose an off-screen/FBO render path through
+    // the API this crate uses, so decoupling capture resolution from the
+    // window (for headless high-resolution renders) is not implemented;
+    // resize the window itself if a different capture resolution is
+    // needed.
+    pub fn start_recording<P: AsRef<Path>>(&mut self, dir: P) {
+        self.recording = Some(Recording {
+                                   dir: dir.as_ref().to_owned(),
+                                   frame: 0,
+                               });
+    }
+    pub fn capture_frame_if_recording(&mut self) {
+        let (dir, frame) = match self.recording {
+            Some(ref mut recording) => {
+                let frame = recording.frame;
+                recording.frame += 1;
+                (recording.dir.clone(), frame)
+            }
+            None => return,
+        };
+        let path = dir.join(format!("frame_{:06}.png", frame));
+        if let Err(e) = self.save_screenshot(&path) {
+            error!("failed to capture frame {}: {}", frame, e);
+        }
+    }
+    pub fn start_server(&mut self, port: u16) {
+        self.remote = Some(server::RemoteServer::start(port));
+    }
+    pub fn apply_remote_commands(&mut self, robot: &mut k::LinkTree<f32>) {
+        let commands = match self.remote {
+            Some(ref remote) => remote.drain_commands(),
+            None => return,
+        };
+        for command in commands {
+            match command {
+                server::Command::SetJoints(joints) => {
+                    for (name, angle) in joints {
+                        if let Err(e) = robot.set_joint_angle_by_name(&name, angle as f32) {
+                            error!("failed to set joint {}: {}", name, e);
+                        }
+                    }
+                }
+                server::Command::SetColor(link, r, g, b) => self.set_temporal_color(&link, r, g, b),
+                server::Command::ResetColor(link) => self.reset_temporal_color(&link),
+            }
+        }
+    }
+    pub fn publish_remote_links(&self, robot: &mut k::LinkTree<f32>) {
+        if let Some(ref remote) = self.remote {
+            let links = robot
+                .calc_link_transforms()
+                .iter()
+                .zip(robot.map_link(&|link| link.name.clone()))
+                .map(|(trans, name)| {
+                    let t = trans.translation.vector;
+                    let r = trans.rotation;
+                    server::LinkTransform {
+                        name: name,
+                        translation: [t[0], t[1], t[2]],
+                        rotation: [r.coords[0], r.coords[1], r.coords[2], r.coords[3]],
+                    }
+                })
+                .collect();
+            remote.publish_links(links);
         }
     }
     pub fn setup(&mut self, base_dir: &Path) {
@@ -216,12 +514,72 @@ impl Viewer {
         self.window.set_background_color(0.0, 0.0, 0.3);
         for l in &self.urdf_robot.links {
             if let Some(geom) = add_geometry(&l.visual, base_dir, &mut self.window) {
+                self.link_signatures
+                    .insert(l.name.to_string(), link_signature(l));
                 self.scenes.insert(l.name.to_string(), geom);
             } else {
                 error!("failed to create for {:?}", l.visual);
             }
         }
     }
+    pub fn watch_paths(&mut self, paths: &[PathBuf]) {
+        match watch::AssetWatcher::new(paths) {
+            Ok(watcher) => self.watcher = Some(watcher),
+            Err(e) => error!("failed to start asset watcher: {}", e),
+        }
+    }
+    pub fn reload_if_changed(&mut self, urdf_path: &Path, base_dir: &Path) -> bool {
+        let should_reload = match self.watcher {
+            Some(ref mut watcher) => watcher.should_reload(),
+            None => false,
+        };
+        if !should_reload {
+            return false;
+        }
+        let resolved = match convert_xacro_if_needed_and_get_path(urdf_path) {
+            Ok(path) => path,
+            Err(e) => {
+                error!("failed to reload {:?}: {}", urdf_path, e);
+                return false;
+            }
+        };
+        let new_robot = match urdf_rs::read_file(&resolved) {
+            Ok(robot) => robot,
+            Err(e) => {
+                error!("failed to re-parse {:?}: {}", resolved, e);
+                return false;
+            }
+        };
+        let new_link_names: std::collections::HashSet<&str> =
+            new_robot.links.iter().map(|l| l.name.as_str()).collect();
+        let removed_names: Vec<String> = self.scenes
+            .keys()
+            .filter(|name| !new_link_names.contains(name.as_str()))
+            .cloned()
+            .collect();
+        for name in removed_names {
+            if let Some(mut old) = self.scenes.remove(&name) {
+                old.unlink();
+            }
+            self.link_signatures.remove(&name);
+            self.original_colors.remove(&name);
+        }
+        for l in &new_robot.links {
+            let new_sig = link_signature(l);
+            if self.link_signatures.get(&l.name) == Some(&new_sig) {
+                continue;
+            }
+            if let Some(geom) = add_geometry(&l.visual, base_dir, &mut self.window) {
+                if let Some(mut old) = self.scenes.insert(l.name.to_string(), geom) {
+                    old.unlink();
+                }
+                self.link_signatures.insert(l.name.to_string(), new_sig);
+            }
+        }
+        self.urdf_robot = new_robot;
+        info!("reloaded {:?} after change", urdf_path);
+        true
+    }
     pub fn add_axis_cylinders(&mut self, name: &str, size: f32) {
         let mut axis_group = self.window.add_group();
         let mut x = axis_group.add_cylinder(0.01, size);
@@ -271,6 +629,13 @@ impl Viewer {
                            .or_insert(kiss3d::text::Font::from_memory(self.font_data, size)),
                        color);
     }
+    pub fn draw_playback_time(&mut self, seconds: f64) {
+        let text = format!("t = {:.2}s", seconds);
+        self.draw_text(&text,
+                        60,
+                        &na::Point2::new(10.0, 10.0),
+                        &na::Point3::new(1.0, 1.0, 1.0));
+    }
     pub fn events(&self) -> kiss3d::window::EventManager {
         self.window.events()
     }
@@ -327,6 +692,22 @@ pub struct Opt {
                 help = "limit the dof for ik to avoid use fingers as end effectors",
                 default_value = "6")]
     pub ik_dof: usize,
+    #[structopt(short = "s", long = "server",
+                help = "run a remote control server on this port (127.0.0.1 only), exposing \
+                        joint commands and link transforms over HTTP")]
+    pub server_port: Option<u16>,
+    #[structopt(long = "play",
+                help = "play back a recorded trajectory (json or csv) instead of interactive \
+                        posing")]
+    pub play_trajectory: Option<String>,
+    #[structopt(long = "loop", help = "loop the --play trajectory instead of stopping at its end")]
+    pub play_loop: bool,
+    #[structopt(long = "speed", default_value = "1.0",
+                help = "playback speed multiplier for --play")]
+    pub play_speed: f64,
+    #[structopt(long = "record",
+                help = "write one numbered PNG per rendered frame to this directory")]
+    pub record_dir: Option<String>,
     #[structopt(help = "Input urdf or xacro")]
     pub input_urdf_or_xacro: String,
 }
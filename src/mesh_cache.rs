@@ -0,0 +1,274 @@
+//! Persistent, checksum-keyed cache of imported meshes so repeated launches
+//! don't re-run assimp on every mesh. Each entry is a deflate-compressed
+//! binary blob named after a hash of the source file's bytes plus its
+//! URDF scale, so edited meshes are re-imported automatically.
+
+extern crate flate2;
+
+use self::flate2::read::DeflateDecoder;
+use self::flate2::write::DeflateEncoder;
+use self::flate2::Compression;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+// Bumped whenever the on-disk blob layout changes so old caches are
+// transparently invalidated instead of misread.
+const CACHE_FORMAT_VERSION: u8 = 1;
+
+pub struct CachedMesh {
+    pub vertices: Vec<[f32; 3]>,
+    pub indices: Vec<[u32; 3]>,
+    pub normals: Option<Vec<[f32; 3]>>,
+    pub uvs: Option<Vec<[f32; 2]>>,
+    pub diffuse_texture: Option<String>,
+}
+
+fn hash_source(bytes: &[u8], scale: [f64; 3]) -> u64 {
+    // FNV-1a: simple, dependency-free, and stable across runs, which is all
+    // a cache key needs to be.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut mix = |byte: u8| {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    };
+    for &byte in bytes {
+        mix(byte);
+    }
+    for component in &scale {
+        for &byte in &component.to_le_bytes() {
+            mix(byte);
+        }
+    }
+    hash
+}
+
+pub fn cache_key(mesh_path: &Path, scale: [f64; 3]) -> Result<u64, String> {
+    let mut file = File::open(mesh_path).map_err(|e| e.to_string())?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(hash_source(&bytes, scale))
+}
+
+fn cache_entry_path(cache_dir: &Path, key: u64) -> PathBuf {
+    cache_dir.join(format!("{:016x}.meshcache", key))
+}
+
+// Returns `None` on a miss or a version mismatch (treated the same as a
+// miss so the caller just re-imports).
+pub fn load(cache_dir: &Path, key: u64) -> Option<Vec<CachedMesh>> {
+    let path = cache_entry_path(cache_dir, key);
+    let mut file = File::open(path).ok()?;
+    let mut compressed = Vec::new();
+    file.read_to_end(&mut compressed).ok()?;
+    let mut decoder = DeflateDecoder::new(&compressed[..]);
+    let mut buf = Vec::new();
+    decoder.read_to_end(&mut buf).ok()?;
+    decode(&buf)
+}
+
+pub fn store(cache_dir: &Path, key: u64, meshes: &[CachedMesh]) -> Result<(), String> {
+    std::fs::create_dir_all(cache_dir).map_err(|e| e.to_string())?;
+    let encoded = encode(meshes);
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&encoded).map_err(|e| e.to_string())?;
+    let compressed = encoder.finish().map_err(|e| e.to_string())?;
+    let path = cache_entry_path(cache_dir, key);
+    let mut file = File::create(path).map_err(|e| e.to_string())?;
+    file.write_all(&compressed).map_err(|e| e.to_string())
+}
+
+fn encode(meshes: &[CachedMesh]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.push(CACHE_FORMAT_VERSION);
+    write_u32(&mut buf, meshes.len() as u32);
+    for mesh in meshes {
+        write_u32(&mut buf, mesh.vertices.len() as u32);
+        for v in &mesh.vertices {
+            write_f32s(&mut buf, v);
+        }
+        write_u32(&mut buf, mesh.indices.len() as u32);
+        for i in &mesh.indices {
+            for component in i {
+                write_u32(&mut buf, *component);
+            }
+        }
+        write_optional_array3(&mut buf, &mesh.normals);
+        write_optional_array2(&mut buf, &mesh.uvs);
+        write_optional_string(&mut buf, &mesh.diffuse_texture);
+    }
+    buf
+}
+
+fn decode(buf: &[u8]) -> Option<Vec<CachedMesh>> {
+    let mut cursor = 0usize;
+    let version = *buf.get(cursor)?;
+    cursor += 1;
+    if version != CACHE_FORMAT_VERSION {
+        return None;
+    }
+    let mesh_count = read_u32(buf, &mut cursor)?;
+    let mut meshes = Vec::with_capacity(mesh_count as usize);
+    for _ in 0..mesh_count {
+        let vertex_count = read_u32(buf, &mut cursor)?;
+        let mut vertices = Vec::with_capacity(vertex_count as usize);
+        for _ in 0..vertex_count {
+            vertices.push(read_f32s(buf, &mut cursor)?);
+        }
+        let index_count = read_u32(buf, &mut cursor)?;
+        let mut indices = Vec::with_capacity(index_count as usize);
+        for _ in 0..index_count {
+            let a = read_u32(buf, &mut cursor)?;
+            let b = read_u32(buf, &mut cursor)?;
+            let c = read_u32(buf, &mut cursor)?;
+            indices.push([a, b, c]);
+        }
+        let normals = read_optional_array3(buf, &mut cursor)?;
+        let uvs = read_optional_array2(buf, &mut cursor)?;
+        let diffuse_texture = read_optional_string(buf, &mut cursor)?;
+        meshes.push(CachedMesh {
+                        vertices: vertices,
+                        indices: indices,
+                        normals: normals,
+                        uvs: uvs,
+                        diffuse_texture: diffuse_texture,
+                    });
+    }
+    Some(meshes)
+}
+
+fn write_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn read_u32(buf: &[u8], cursor: &mut usize) -> Option<u32> {
+    let bytes = buf.get(*cursor..*cursor + 4)?;
+    *cursor += 4;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn write_f32s(buf: &mut Vec<u8>, values: &[f32; 3]) {
+    for value in values {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn read_f32s(buf: &[u8], cursor: &mut usize) -> Option<[f32; 3]> {
+    let mut out = [0.0f32; 3];
+    for slot in &mut out {
+        let bytes = buf.get(*cursor..*cursor + 4)?;
+        *cursor += 4;
+        *slot = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    Some(out)
+}
+
+fn write_optional_array3(buf: &mut Vec<u8>, values: &Option<Vec<[f32; 3]>>) {
+    match *values {
+        Some(ref array) => {
+            buf.push(1);
+            write_u32(buf, array.len() as u32);
+            for v in array {
+                write_f32s(buf, v);
+            }
+        }
+        None => buf.push(0),
+    }
+}
+
+fn read_optional_array3(buf: &[u8], cursor: &mut usize) -> Option<Option<Vec<[f32; 3]>>> {
+    let tag = *buf.get(*cursor)?;
+    *cursor += 1;
+    if tag == 0 {
+        return Some(None);
+    }
+    let count = read_u32(buf, cursor)?;
+    let mut array = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        array.push(read_f32s(buf, cursor)?);
+    }
+    Some(Some(array))
+}
+
+fn write_optional_array2(buf: &mut Vec<u8>, values: &Option<Vec<[f32; 2]>>) {
+    match *values {
+        Some(ref array) => {
+            buf.push(1);
+            write_u32(buf, array.len() as u32);
+            for v in array {
+                buf.extend_from_slice(&v[0].to_le_bytes());
+                buf.extend_from_slice(&v[1].to_le_bytes());
+            }
+        }
+        None => buf.push(0),
+    }
+}
+
+fn read_optional_array2(buf: &[u8], cursor: &mut usize) -> Option<Option<Vec<[f32; 2]>>> {
+    let tag = *buf.get(*cursor)?;
+    *cursor += 1;
+    if tag == 0 {
+        return Some(None);
+    }
+    let count = read_u32(buf, cursor)?;
+    let mut array = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let x_bytes = buf.get(*cursor..*cursor + 4)?;
+        *cursor += 4;
+        let y_bytes = buf.get(*cursor..*cursor + 4)?;
+        *cursor += 4;
+        array.push([f32::from_le_bytes([x_bytes[0], x_bytes[1], x_bytes[2], x_bytes[3]]),
+                    f32::from_le_bytes([y_bytes[0], y_bytes[1], y_bytes[2], y_bytes[3]])]);
+    }
+    Some(Some(array))
+}
+
+fn write_optional_string(buf: &mut Vec<u8>, value: &Option<String>) {
+    match *value {
+        Some(ref s) => {
+            buf.push(1);
+            let bytes = s.as_bytes();
+            write_u32(buf, bytes.len() as u32);
+            buf.extend_from_slice(bytes);
+        }
+        None => buf.push(0),
+    }
+}
+
+fn read_optional_string(buf: &[u8], cursor: &mut usize) -> Option<Option<String>> {
+    let tag = *buf.get(*cursor)?;
+    *cursor += 1;
+    if tag == 0 {
+        return Some(None);
+    }
+    let len = read_u32(buf, cursor)? as usize;
+    let bytes = buf.get(*cursor..*cursor + len)?;
+    *cursor += len;
+    Some(Some(String::from_utf8(bytes.to_vec()).ok()?))
+}
+
+#[test]
+fn test_roundtrip() {
+    let meshes = vec![CachedMesh {
+                           vertices: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+                           indices: vec![[0, 1, 2]],
+                           normals: Some(vec![[0.0, 0.0, 1.0], [0.0, 0.0, 1.0], [0.0, 0.0, 1.0]]),
+                           uvs: None,
+                           diffuse_texture: Some("diffuse.png".to_owned()),
+                       }];
+    let encoded = encode(&meshes);
+    let decoded = decode(&encoded).unwrap();
+    assert_eq!(decoded.len(), 1);
+    assert_eq!(decoded[0].vertices, meshes[0].vertices);
+    assert_eq!(decoded[0].indices, meshes[0].indices);
+    assert_eq!(decoded[0].normals, meshes[0].normals);
+    assert_eq!(decoded[0].uvs, meshes[0].uvs);
+    assert_eq!(decoded[0].diffuse_texture, meshes[0].diffuse_texture);
+}
+
+#[test]
+fn test_hash_changes_with_scale() {
+    let a = hash_source(b"mesh bytes", [1.0, 1.0, 1.0]);
+    let b = hash_source(b"mesh bytes", [2.0, 1.0, 1.0]);
+    assert_ne!(a, b);
+}
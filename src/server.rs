@@ -0,0 +1,136 @@
+//! Remote control server: lets external processes (motion planners, teleop
+//! scripts, ...) drive a `Viewer` over a plain polling HTTP API instead of
+//! keyboard input.
+//!
+//! The HTTP listener runs on its own background thread; the render loop
+//! owns the `Window`/GL context and must stay single-threaded, so the
+//! server only ever pushes `Command`s onto a channel that the render loop
+//! drains at the top of each frame via `drain_commands`.
+
+extern crate serde_json;
+extern crate tiny_http;
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+pub enum Command {
+    /// `POST /joints`: joint name -> angle (radians).
+    SetJoints(HashMap<String, f64>),
+    /// `POST /color`: set a link's temporal color.
+    SetColor(String, f32, f32, f32),
+    /// `POST /color`: reset a link back to its original color.
+    ResetColor(String),
+}
+
+/// Snapshot of a link transform, serialized as translation + quaternion so
+/// `GET /links` can hand it back as plain JSON.
+#[derive(Serialize)]
+pub struct LinkTransform {
+    pub name: String,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+pub struct RemoteServer {
+    command_rx: Receiver<Command>,
+    links_tx: Sender<Vec<LinkTransform>>,
+}
+
+impl RemoteServer {
+    pub fn start(port: u16) -> RemoteServer {
+        RemoteServer::start_on("127.0.0.1", port)
+    }
+
+    /// Like `start`, but binds `addr` instead of the loopback interface.
+    /// The `/joints` and `/color` endpoints take unauthenticated commands
+    /// that move the real robot model, so only bind a non-loopback
+    /// address on a trusted network.
+    pub fn start_on(addr: &str, port: u16) -> RemoteServer {
+        let (command_tx, command_rx) = channel();
+        let (links_tx, links_rx) = channel();
+        let server = tiny_http::Server::http((addr, port))
+            .unwrap_or_else(|e| panic!("failed to bind server on {}:{}: {}", addr, port, e));
+        thread::spawn(move || serve_forever(server, command_tx, links_rx));
+        RemoteServer {
+            command_rx: command_rx,
+            links_tx: links_tx,
+        }
+    }
+
+    pub fn drain_commands(&self) -> Vec<Command> {
+        self.command_rx.try_iter().collect()
+    }
+
+    pub fn publish_links(&self, links: Vec<LinkTransform>) {
+        // The send only fails once the server thread has exited and dropped
+        // its receiver; that's not an error for the render loop.
+        let _ = self.links_tx.send(links);
+    }
+}
+
+fn serve_forever(server: tiny_http::Server,
+                  command_tx: Sender<Command>,
+                  links_rx: Receiver<Vec<LinkTransform>>) {
+    let mut last_links: Vec<LinkTransform> = Vec::new();
+    for mut request in server.incoming_requests() {
+        while let Ok(links) = links_rx.try_recv() {
+            last_links = links;
+        }
+        let url = request.url().to_owned();
+        let mut body = String::new();
+        let _ = request.as_reader().read_to_string(&mut body);
+        let response = match (request.method(), url.as_str()) {
+            (&tiny_http::Method::Post, "/joints") => {
+                match serde_json::from_str::<HashMap<String, f64>>(&body) {
+                    Ok(joints) => {
+                        let _ = command_tx.send(Command::SetJoints(joints));
+                        tiny_http::Response::from_string("{}".to_owned())
+                    }
+                    Err(e) => {
+                        tiny_http::Response::from_string(format!("{{\"error\":\"{}\"}}", e))
+                            .with_status_code(400)
+                    }
+                }
+            }
+            (&tiny_http::Method::Get, "/links") => {
+                let json = serde_json::to_string(&last_links)
+                    .unwrap_or_else(|_| "[]".to_owned());
+                tiny_http::Response::from_string(json)
+            }
+            (&tiny_http::Method::Post, "/color") => {
+                match serde_json::from_str::<ColorRequest>(&body) {
+                    Ok(req) => {
+                        let cmd = match req.reset {
+                            Some(true) => Command::ResetColor(req.link),
+                            _ => Command::SetColor(req.link, req.r, req.g, req.b),
+                        };
+                        let _ = command_tx.send(cmd);
+                        tiny_http::Response::from_string("{}".to_owned())
+                    }
+                    Err(e) => {
+                        tiny_http::Response::from_string(format!("{{\"error\":\"{}\"}}", e))
+                            .with_status_code(400)
+                    }
+                }
+            }
+            _ => tiny_http::Response::from_string("{\"error\":\"not found\"}".to_owned())
+                .with_status_code(404),
+        };
+        let _ = request.respond(response);
+    }
+}
+
+#[derive(Deserialize)]
+struct ColorRequest {
+    link: String,
+    #[serde(default)]
+    r: f32,
+    #[serde(default)]
+    g: f32,
+    #[serde(default)]
+    b: f32,
+    #[serde(default)]
+    reset: Option<bool>,
+}
@@ -0,0 +1,252 @@
+//! Trajectory playback: animate a robot through a recorded motion instead
+//! of only interactive posing.
+
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+pub struct Keyframe {
+    pub time: f64,
+    pub joint_positions: HashMap<String, f64>,
+}
+
+pub struct Trajectory {
+    keyframes: Vec<Keyframe>,
+}
+
+impl Trajectory {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Trajectory, String> {
+        let path = path.as_ref();
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|e| format!("failed to read trajectory {:?}: {}", path, e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => parse_json(&contents),
+            Some("csv") => parse_csv(&contents),
+            other => Err(format!("unsupported trajectory extension: {:?}", other)),
+        }
+    }
+
+    pub fn sample(&self, time: f64) -> Option<HashMap<String, f64>> {
+        if self.keyframes.is_empty() {
+            return None;
+        }
+        if time <= self.keyframes[0].time {
+            return Some(self.keyframes[0].joint_positions.clone());
+        }
+        let last = self.keyframes.len() - 1;
+        if time >= self.keyframes[last].time {
+            return Some(self.keyframes[last].joint_positions.clone());
+        }
+        let next_index = self.keyframes
+            .iter()
+            .position(|k| k.time > time)
+            .unwrap_or(last);
+        let prev = &self.keyframes[next_index - 1];
+        let next = &self.keyframes[next_index];
+        let span = next.time - prev.time;
+        let ratio = if span > 0.0 { (time - prev.time) / span } else { 0.0 };
+        let mut positions = HashMap::new();
+        for (name, &prev_value) in &prev.joint_positions {
+            let next_value = next.joint_positions.get(name).cloned().unwrap_or(prev_value);
+            positions.insert(name.clone(), lerp_angle(prev_value, next_value, ratio));
+        }
+        Some(positions)
+    }
+
+    pub fn duration(&self) -> f64 {
+        match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.0,
+        }
+    }
+}
+
+// Interpolates via the shortest angular path, so a joint crossing +-pi
+// doesn't spin the long way around during playback.
+fn lerp_angle(from: f64, to: f64, ratio: f64) -> f64 {
+    let mut delta = (to - from) % (2.0 * PI);
+    if delta > PI {
+        delta -= 2.0 * PI;
+    } else if delta < -PI {
+        delta += 2.0 * PI;
+    }
+    from + delta * ratio
+}
+
+fn parse_json(contents: &str) -> Result<Trajectory, String> {
+    #[derive(Deserialize)]
+    struct RawKeyframe {
+        time: f64,
+        joints: HashMap<String, f64>,
+    }
+    let raw: Vec<RawKeyframe> =
+        serde_json::from_str(contents).map_err(|e| format!("invalid trajectory json: {}", e))?;
+    let mut keyframes: Vec<Keyframe> = raw.into_iter()
+        .map(|k| {
+                 Keyframe {
+                     time: k.time,
+                     joint_positions: k.joints,
+                 }
+             })
+        .collect();
+    keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    Ok(Trajectory { keyframes: keyframes })
+}
+
+fn parse_csv(contents: &str) -> Result<Trajectory, String> {
+    let mut lines = contents.lines();
+    let header = lines.next().ok_or_else(|| "empty trajectory csv".to_owned())?;
+    let columns: Vec<&str> = header.split(',').map(|c| c.trim()).collect();
+    let time_index = columns
+        .iter()
+        .position(|&c| c == "time")
+        .ok_or_else(|| "trajectory csv missing a 'time' column".to_owned())?;
+    let mut keyframes = Vec::new();
+    for line in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if fields.len() != columns.len() {
+            return Err(format!("trajectory csv row has {} fields, expected {}: {:?}",
+                                fields.len(),
+                                columns.len(),
+                                line));
+        }
+        let time: f64 = fields[time_index]
+            .parse()
+            .map_err(|e| format!("invalid time in trajectory csv: {}", e))?;
+        let mut joint_positions = HashMap::new();
+        for (index, &column) in columns.iter().enumerate() {
+            if index == time_index {
+                continue;
+            }
+            let value: f64 = fields[index]
+                .parse()
+                .map_err(|e| format!("invalid value for joint {}: {}", column, e))?;
+            joint_positions.insert(column.to_owned(), value);
+        }
+        keyframes.push(Keyframe {
+                            time: time,
+                            joint_positions: joint_positions,
+                        });
+    }
+    keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    Ok(Trajectory { keyframes: keyframes })
+}
+
+pub struct Player {
+    trajectory: Trajectory,
+    elapsed: f64,
+    pub speed: f64,
+    pub looping: bool,
+    finished: bool,
+}
+
+impl Player {
+    pub fn new(trajectory: Trajectory, speed: f64, looping: bool) -> Player {
+        Player {
+            trajectory: trajectory,
+            elapsed: 0.0,
+            speed: speed,
+            looping: looping,
+            finished: false,
+        }
+    }
+
+    // A non-looping trajectory still returns `Some` with its final pose on
+    // the frame it finishes on; only calls made after that return `None`.
+    pub fn advance(&mut self, dt: f64) -> Option<HashMap<String, f64>> {
+        if self.finished {
+            return None;
+        }
+        self.elapsed += dt * self.speed;
+        let duration = self.trajectory.duration();
+        if self.elapsed >= duration {
+            if self.looping && duration > 0.0 {
+                self.elapsed %= duration;
+            } else {
+                self.elapsed = duration;
+                self.finished = true;
+            }
+        }
+        // `elapsed` runs from 0 to `duration`; keyframe times are absolute.
+        let start = self.trajectory.keyframes.first().map(|k| k.time).unwrap_or(0.0);
+        self.trajectory.sample(start + self.elapsed)
+    }
+
+    pub fn current_time(&self) -> f64 {
+        self.elapsed
+    }
+}
+
+#[test]
+fn test_lerp_angle_shortest_path() {
+    let almost_pi = PI - 0.1;
+    let almost_neg_pi = -PI + 0.1;
+    let mid = lerp_angle(almost_pi, almost_neg_pi, 0.5);
+    assert!(mid.abs() > PI - 0.2);
+}
+
+#[test]
+fn test_trajectory_sample_interpolates() {
+    let mut a = HashMap::new();
+    a.insert("j1".to_owned(), 0.0);
+    let mut b = HashMap::new();
+    b.insert("j1".to_owned(), 1.0);
+    let trajectory = Trajectory {
+        keyframes: vec![Keyframe {
+                             time: 0.0,
+                             joint_positions: a,
+                         },
+                        Keyframe {
+                            time: 2.0,
+                            joint_positions: b,
+                        }],
+    };
+    let sample = trajectory.sample(1.0).unwrap();
+    assert!((sample["j1"] - 0.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_parse_csv_rejects_ragged_row() {
+    let csv = "time,j1\n0.0,0.0\n1.0,0.5,extra\n";
+    let result = parse_csv(csv);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_player_delivers_final_pose_once() {
+    let mut a = HashMap::new();
+    a.insert("j1".to_owned(), 0.0);
+    let mut b = HashMap::new();
+    b.insert("j1".to_owned(), 1.0);
+    let trajectory = Trajectory {
+        keyframes: vec![Keyframe {
+                             time: 0.0,
+                             joint_positions: a,
+                         },
+                        Keyframe {
+                            time: 1.0,
+                            joint_positions: b,
+                        }],
+    };
+    let mut player = Player::new(trajectory, 1.0, false);
+    let final_pose = player.advance(1.0).unwrap();
+    assert!((final_pose["j1"] - 1.0).abs() < 1e-9);
+    assert!(player.advance(0.1).is_none());
+}
+
+#[test]
+fn test_parse_csv_sorts_out_of_order_rows() {
+    let csv = "time,j1\n1.0,1.0\n0.0,0.0\n";
+    let trajectory = parse_csv(csv).unwrap();
+    let sample = trajectory.sample(0.0).unwrap();
+    assert!((sample["j1"] - 0.0).abs() < 1e-9);
+}
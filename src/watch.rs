@@ -0,0 +1,53 @@
+//! Filesystem watching for hot-reloading URDF/xacro and mesh assets.
+
+extern crate notify;
+
+use self::notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::time::Duration;
+
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+pub struct AssetWatcher {
+    _watcher: RecommendedWatcher,
+    rx: Receiver<DebouncedEvent>,
+}
+
+impl AssetWatcher {
+    pub fn new(paths: &[PathBuf]) -> Result<AssetWatcher, String> {
+        let (tx, rx) = channel();
+        let mut watcher = notify::watcher(tx, DEBOUNCE)
+            .map_err(|e| format!("failed to create watcher: {}", e))?;
+        for path in paths {
+            if let Some(dir) = path.parent() {
+                watcher
+                    .watch(dir, RecursiveMode::NonRecursive)
+                    .map_err(|e| format!("failed to watch {:?}: {}", dir, e))?;
+            }
+        }
+        Ok(AssetWatcher {
+               _watcher: watcher,
+               rx: rx,
+           })
+    }
+
+    pub fn should_reload(&mut self) -> bool {
+        let mut reload = false;
+        loop {
+            match self.rx.try_recv() {
+                // notify's own debounce already coalesces bursts, so any
+                // other event reaching us is a real, settled change. The
+                // Notice* events are sent immediately on the first write or
+                // remove, before the burst settles, so they are skipped too.
+                Ok(DebouncedEvent::Rescan) |
+                Ok(DebouncedEvent::Error(..)) |
+                Ok(DebouncedEvent::NoticeWrite(..)) |
+                Ok(DebouncedEvent::NoticeRemove(..)) => {}
+                Ok(_event) => reload = true,
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        reload
+    }
+}